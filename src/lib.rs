@@ -1,10 +1,116 @@
+use std::borrow::Cow;
+use std::fmt;
+
+mod properties;
+mod topic;
+
 use nom::{
-    bits::{bits, complete::take},
+    bits::{bits, streaming::take},
+    bytes::streaming::take as take_bytes,
     combinator::map_res,
+    error::{FromExternalError, ParseError},
+    number::streaming::{be_u16, be_u8},
     sequence::tuple,
-    IResult,
+    ErrorConvert, IResult,
 };
 
+use properties::Properties;
+
+/// Errors that can occur while parsing an MQTT Control Packet
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Error {
+    InvalidPacketType(u8),
+    InvalidFixedHeaderFlags(PacketType, u8),
+    InvalidQoS(u8),
+    MalformedRemainingLength,
+    InvalidPropertyIdentifier(u8),
+    MalformedUtf8String,
+    TopicNotUtf8,
+    TopicMalformed,
+    PacketIdZero,
+    PayloadNotEmpty(PacketType),
+    Nom(nom::error::ErrorKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidPacketType(value) => write!(f, "invalid packet type: {}", value),
+            Error::InvalidFixedHeaderFlags(packet_type, flags) => write!(
+                f,
+                "invalid fixed header flags 0b{:04b} for {:?}",
+                flags, packet_type
+            ),
+            Error::InvalidQoS(value) => write!(f, "invalid QoS value: {}", value),
+            Error::MalformedRemainingLength => {
+                write!(f, "malformed remaining length: more than four bytes")
+            }
+            Error::InvalidPropertyIdentifier(identifier) => {
+                write!(f, "invalid property identifier: 0x{:02x}", identifier)
+            }
+            Error::MalformedUtf8String => write!(f, "string is not valid UTF-8"),
+            Error::TopicNotUtf8 => write!(f, "topic is not valid UTF-8"),
+            Error::TopicMalformed => write!(f, "topic violates the MQTT wildcard rules"),
+            Error::PacketIdZero => write!(f, "packet identifier must not be zero"),
+            Error::PayloadNotEmpty(packet_type) => {
+                write!(f, "{:?} does not carry a payload", packet_type)
+            }
+            Error::Nom(kind) => write!(f, "parsing error: {:?}", kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<InvalidPacketTypeError> for Error {
+    fn from(err: InvalidPacketTypeError) -> Self {
+        Error::InvalidPacketType(err.0)
+    }
+}
+
+impl From<InvalidFixedHeaderFlagsError> for Error {
+    fn from(err: InvalidFixedHeaderFlagsError) -> Self {
+        Error::InvalidFixedHeaderFlags(err.0, err.1)
+    }
+}
+
+impl From<InvalidQoSError> for Error {
+    fn from(err: InvalidQoSError) -> Self {
+        Error::InvalidQoS(err.0)
+    }
+}
+
+impl<'a> ParseError<&'a [u8]> for Error {
+    fn from_error_kind(_input: &'a [u8], kind: nom::error::ErrorKind) -> Self {
+        Error::Nom(kind)
+    }
+
+    fn append(_input: &'a [u8], _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'a> FromExternalError<&'a [u8], Error> for Error {
+    fn from_external_error(_input: &'a [u8], _kind: nom::error::ErrorKind, e: Error) -> Self {
+        e
+    }
+}
+
+impl<'a> ErrorConvert<Error> for nom::error::Error<(&'a [u8], usize)> {
+    fn convert(self) -> Error {
+        Error::Nom(self.code)
+    }
+}
+
+/// Which revision of the MQTT spec a buffer should be parsed as. Gates
+/// behaviour that differs between versions, such as the v5-only AUTH packet
+/// and its properties subsystem.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum ProtocolVersion {
+    V311,
+    V5,
+}
+
 /// The MQTT Control packet types
 /// ref: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Table_2.1_-
 #[derive(Debug, PartialEq, Clone)]
@@ -23,13 +129,35 @@ enum PacketType {
     PingReq,
     PingResp,
     Disconnect,
+    /// v5 only
+    Auth,
 }
 
-struct InvalidPacketTypeError(u8);
+impl PacketType {
+    /// The packet type nibble as it appears in the fixed header's first byte
+    fn as_u8(&self) -> u8 {
+        match self {
+            PacketType::Connect => 1,
+            PacketType::Connack => 2,
+            PacketType::Publish => 3,
+            PacketType::Puback => 4,
+            PacketType::PubRec => 5,
+            PacketType::PubRel => 6,
+            PacketType::PubComp => 7,
+            PacketType::Subscribe => 8,
+            PacketType::Suback => 9,
+            PacketType::Unsubscribe => 10,
+            PacketType::Unsuback => 11,
+            PacketType::PingReq => 12,
+            PacketType::PingResp => 13,
+            PacketType::Disconnect => 14,
+            PacketType::Auth => 15,
+        }
+    }
 
-impl TryFrom<u8> for PacketType {
-    type Error = InvalidPacketTypeError;
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
+    /// Decode the packet type nibble, rejecting the v5-only AUTH type (15)
+    /// unless `version` is `ProtocolVersion::V5`.
+    fn decode(value: u8, version: ProtocolVersion) -> Result<Self, InvalidPacketTypeError> {
         match value {
             1 => Ok(PacketType::Connect),
             2 => Ok(PacketType::Connack),
@@ -45,11 +173,63 @@ impl TryFrom<u8> for PacketType {
             12 => Ok(PacketType::PingReq),
             13 => Ok(PacketType::PingResp),
             14 => Ok(PacketType::Disconnect),
+            15 if version == ProtocolVersion::V5 => Ok(PacketType::Auth),
             _ => Err(InvalidPacketTypeError(value)),
         }
     }
 }
 
+struct InvalidPacketTypeError(u8);
+
+/// Quality of Service level a PUBLISH message is delivered with
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+    ExactlyOnce = 2,
+}
+
+struct InvalidQoSError(u8);
+
+impl TryFrom<u8> for QoS {
+    type Error = InvalidQoSError;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            _ => Err(InvalidQoSError(value)),
+        }
+    }
+}
+
+/// Decode the PUBLISH fixed header flag nibble (bit 3 `DUP`, bits 2-1 `QoS`,
+/// bit 0 `RETAIN`), rejecting the reserved QoS value 3.
+fn publish_flags(packet_flags: u8) -> Result<(bool, QoS, bool), InvalidQoSError> {
+    let dup = packet_flags & 0b1000 != 0;
+    let qos = ((packet_flags & 0b0110) >> 1).try_into()?;
+    let retain = packet_flags & 0b0001 != 0;
+    Ok((dup, qos, retain))
+}
+
+struct InvalidFixedHeaderFlagsError(PacketType, u8);
+
+/// PUBREL, SUBSCRIBE and UNSUBSCRIBE require their fixed header flag nibble
+/// to be the fixed value `0b0010`.
+fn validate_fixed_flags(
+    packet_type: &PacketType,
+    packet_flags: u8,
+) -> Result<(), InvalidFixedHeaderFlagsError> {
+    let requires_reserved_flags = matches!(
+        packet_type,
+        PacketType::PubRel | PacketType::Subscribe | PacketType::Unsubscribe
+    );
+    if requires_reserved_flags && packet_flags != 0b0010 {
+        return Err(InvalidFixedHeaderFlagsError(packet_type.clone(), packet_flags));
+    }
+    Ok(())
+}
+
 #[derive(Debug, PartialEq, Clone)]
 struct FixedHeader {
     /// MQTT Control Packet type
@@ -58,15 +238,17 @@ struct FixedHeader {
     packet_flags: u8,
     /// the number of bytes remaining within the current packet,
     /// including data in the variable header and the payload.
-    remaining_length: i32,
+    remaining_length: u32,
 }
 
 impl FixedHeader {
-    fn parse(input: &[u8]) -> IResult<&[u8], Self> {
-        for a in input {
-            println!("Parsing 0b{:08b}", a);
-        }
-        let (input, (packet_type, packet_flags)) = packet_byte(input)?;
+    /// Parse a v3.1.1 fixed header; the AUTH packet type is rejected.
+    fn parse(input: &[u8]) -> IResult<&[u8], Self, Error> {
+        Self::parse_with_version(input, ProtocolVersion::V311)
+    }
+
+    fn parse_with_version(input: &[u8], version: ProtocolVersion) -> IResult<&[u8], Self, Error> {
+        let (input, (packet_type, packet_flags)) = packet_byte(input, version)?;
         let (input, remaining_length) = remaining_length(input)?;
         Ok((
             input,
@@ -77,49 +259,516 @@ impl FixedHeader {
             },
         ))
     }
+
+    /// Encode the fixed header back to its wire representation: the packet
+    /// type/flags byte followed by `remaining_length` as a variable byte integer.
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push((self.packet_type.as_u8() << 4) | (self.packet_flags & 0x0F));
+        encode_remaining_length(self.remaining_length, buf);
+    }
+}
+
+/// Encode a remaining-length value as an MQTT variable byte integer: the low
+/// 7 bits of each byte hold the value, with the high bit set while more
+/// bytes follow.
+pub(crate) fn encode_remaining_length(mut value: u32, buf: &mut Vec<u8>) {
+    loop {
+        let mut byte = (value % 128) as u8;
+        value /= 128;
+        if value > 0 {
+            byte |= 0b1000_0000;
+        }
+        buf.push(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+/// A decoded MQTT Control Packet: the fixed header has been consumed and the
+/// variable header/payload (if any) decoded for the packet's type.
+#[derive(Debug, PartialEq, Clone)]
+enum Packet<'a> {
+    Connect {
+        protocol_name: &'a str,
+        protocol_level: u8,
+        connect_flags: u8,
+        keep_alive: u16,
+        /// v5 only; empty for v3.1.1
+        properties: Properties<'a>,
+        payload: &'a [u8],
+    },
+    Connack {
+        session_present: bool,
+        reason_code: u8,
+        /// v5 only; empty for v3.1.1
+        properties: Properties<'a>,
+        payload: &'a [u8],
+    },
+    Publish {
+        dup: bool,
+        qos: QoS,
+        retain: bool,
+        topic_name: &'a str,
+        /// v5 only; empty for v3.1.1
+        properties: Properties<'a>,
+        payload: &'a [u8],
+    },
+    Puback(&'a [u8]),
+    PubRec(&'a [u8]),
+    PubRel(&'a [u8]),
+    PubComp(&'a [u8]),
+    Subscribe {
+        packet_id: u16,
+        /// v5 only; empty for v3.1.1
+        properties: Properties<'a>,
+        /// (topic filter, subscription options byte) pairs
+        topics: Vec<(&'a str, u8)>,
+    },
+    Suback(&'a [u8]),
+    Unsubscribe {
+        packet_id: u16,
+        /// v5 only; empty for v3.1.1
+        properties: Properties<'a>,
+        topics: Vec<&'a str>,
+    },
+    Unsuback(&'a [u8]),
+    PingReq,
+    PingResp,
+    /// v5 carries an optional reason code + properties; v3.1.1 has neither
+    /// and requires an empty body
+    Disconnect {
+        reason_code: u8,
+        /// v5 only; empty for v3.1.1
+        properties: Properties<'a>,
+    },
+    /// v5 only
+    Auth {
+        reason_code: u8,
+        properties: Properties<'a>,
+        payload: &'a [u8],
+    },
+}
+
+/// Split a v5 packet body into its leading property block and the remaining
+/// payload. For v3.1.1, or a zero-length body (no reason code/properties
+/// present), the properties are empty and the whole body is the payload.
+fn parse_properties<'a>(
+    body: &'a [u8],
+    version: ProtocolVersion,
+) -> IResult<&'a [u8], Properties<'a>, Error> {
+    if version == ProtocolVersion::V5 && !body.is_empty() {
+        Properties::parse(body)
+    } else {
+        Ok((body, Properties::default()))
+    }
+}
+
+/// Parse a SUBSCRIBE payload: a list of (topic filter, subscription options
+/// byte) pairs, one per subscribed topic. An empty payload yields an empty
+/// list; it is not rejected.
+fn parse_subscribe_topics(mut input: &[u8]) -> IResult<&[u8], Vec<(&str, u8)>, Error> {
+    let mut topics = Vec::new();
+    while !input.is_empty() {
+        let (rest, filter) = topic::topic(input)?;
+        topic::validate_topic_filter(filter).map_err(nom::Err::Failure)?;
+        let (rest, options) = be_u8(rest)?;
+        input = rest;
+        topics.push((filter, options));
+    }
+    Ok((input, topics))
+}
+
+/// Parse an UNSUBSCRIBE payload: a list of topic filters. An empty payload
+/// yields an empty list; it is not rejected.
+fn parse_unsubscribe_topics(mut input: &[u8]) -> IResult<&[u8], Vec<&str>, Error> {
+    let mut topics = Vec::new();
+    while !input.is_empty() {
+        let (rest, filter) = topic::topic(input)?;
+        topic::validate_topic_filter(filter).map_err(nom::Err::Failure)?;
+        input = rest;
+        topics.push(filter);
+    }
+    Ok((input, topics))
+}
+
+impl<'a> Packet<'a> {
+    /// Parse a full v3.1.1 MQTT Control Packet; the AUTH packet type is rejected.
+    ///
+    /// Built on nom's streaming combinators, so a buffer that doesn't yet hold
+    /// a whole packet (a partial TCP read) yields `Err(nom::Err::Incomplete(Needed))`
+    /// reporting how many more bytes are required, rather than a hard parse error.
+    /// Callers should read more bytes and retry rather than treating this as malformed input.
+    fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self, Error> {
+        Self::parse_with_version(input, ProtocolVersion::V311)
+    }
+
+    /// Parse a full MQTT Control Packet: the fixed header followed by exactly
+    /// `remaining_length` bytes of variable header and payload, dispatched to
+    /// a per-`PacketType` parser.
+    ///
+    /// Once the buffer holds the whole packet, a body too short for the fields
+    /// its type requires is malformed, and fails instead of yielding `Incomplete`.
+    fn parse_with_version(
+        input: &'a [u8],
+        version: ProtocolVersion,
+    ) -> IResult<&'a [u8], Self, Error> {
+        let (rest, header) = FixedHeader::parse_with_version(input, version)?;
+        let packet_len = input.len() - rest.len() + header.remaining_length as usize;
+        match Self::parse_packet(input, version) {
+            Err(nom::Err::Incomplete(_)) if input.len() >= packet_len => Err(nom::Err::Failure(
+                Error::Nom(nom::error::ErrorKind::Eof),
+            )),
+            result => result,
+        }
+    }
+
+    fn parse_packet(
+        input: &'a [u8],
+        version: ProtocolVersion,
+    ) -> IResult<&'a [u8], Self, Error> {
+        let (input, header) = FixedHeader::parse_with_version(input, version)?;
+        let (input, body) = take_bytes(header.remaining_length as usize)(input)?;
+        let packet = match header.packet_type {
+            PacketType::Connect => {
+                let (rest, protocol_name) = utf8_string(body, || Error::MalformedUtf8String)?;
+                let (rest, protocol_level) = be_u8(rest)?;
+                let (rest, connect_flags) = be_u8(rest)?;
+                let (rest, keep_alive) = be_u16(rest)?;
+                let (payload, properties) = parse_properties(rest, version)?;
+                Packet::Connect {
+                    protocol_name,
+                    protocol_level,
+                    connect_flags,
+                    keep_alive,
+                    properties,
+                    payload,
+                }
+            }
+            PacketType::Connack => {
+                let (rest, ack_flags) = be_u8(body)?;
+                let (rest, reason_code) = be_u8(rest)?;
+                let (payload, properties) = parse_properties(rest, version)?;
+                Packet::Connack {
+                    session_present: ack_flags & 0b1 != 0,
+                    reason_code,
+                    properties,
+                    payload,
+                }
+            }
+            PacketType::Publish => {
+                let (dup, qos, retain) =
+                    publish_flags(header.packet_flags).map_err(|e| nom::Err::Failure(e.into()))?;
+                let (rest, topic_name) = topic::topic(body)?;
+                topic::validate_topic_name(topic_name).map_err(nom::Err::Failure)?;
+                let (payload, properties) = parse_properties(rest, version)?;
+                Packet::Publish {
+                    dup,
+                    qos,
+                    retain,
+                    topic_name,
+                    properties,
+                    payload,
+                }
+            }
+            PacketType::Puback => Packet::Puback(body),
+            PacketType::PubRec => Packet::PubRec(body),
+            PacketType::PubRel => Packet::PubRel(body),
+            PacketType::PubComp => Packet::PubComp(body),
+            PacketType::Subscribe => {
+                let (rest, packet_id) = be_u16(body)?;
+                if packet_id == 0 {
+                    return Err(nom::Err::Failure(Error::PacketIdZero));
+                }
+                let (rest, properties) = parse_properties(rest, version)?;
+                let (_, topics) = parse_subscribe_topics(rest)?;
+                Packet::Subscribe {
+                    packet_id,
+                    properties,
+                    topics,
+                }
+            }
+            PacketType::Suback => Packet::Suback(body),
+            PacketType::Unsubscribe => {
+                let (rest, packet_id) = be_u16(body)?;
+                if packet_id == 0 {
+                    return Err(nom::Err::Failure(Error::PacketIdZero));
+                }
+                let (rest, properties) = parse_properties(rest, version)?;
+                let (_, topics) = parse_unsubscribe_topics(rest)?;
+                Packet::Unsubscribe {
+                    packet_id,
+                    properties,
+                    topics,
+                }
+            }
+            PacketType::Unsuback => Packet::Unsuback(body),
+            PacketType::PingReq => {
+                if !body.is_empty() {
+                    return Err(nom::Err::Failure(Error::PayloadNotEmpty(PacketType::PingReq)));
+                }
+                Packet::PingReq
+            }
+            PacketType::PingResp => {
+                if !body.is_empty() {
+                    return Err(nom::Err::Failure(Error::PayloadNotEmpty(
+                        PacketType::PingResp,
+                    )));
+                }
+                Packet::PingResp
+            }
+            PacketType::Disconnect => {
+                if body.is_empty() {
+                    Packet::Disconnect {
+                        reason_code: 0,
+                        properties: Properties::default(),
+                    }
+                } else if version == ProtocolVersion::V5 {
+                    let (rest, reason_code) = be_u8(body)?;
+                    let (rest, properties) = parse_properties(rest, version)?;
+                    if !rest.is_empty() {
+                        return Err(nom::Err::Failure(Error::PayloadNotEmpty(
+                            PacketType::Disconnect,
+                        )));
+                    }
+                    Packet::Disconnect {
+                        reason_code,
+                        properties,
+                    }
+                } else {
+                    return Err(nom::Err::Failure(Error::PayloadNotEmpty(
+                        PacketType::Disconnect,
+                    )));
+                }
+            }
+            PacketType::Auth => {
+                if body.is_empty() {
+                    Packet::Auth {
+                        reason_code: 0,
+                        properties: Properties::default(),
+                        payload: body,
+                    }
+                } else {
+                    let (rest, reason_code) = be_u8(body)?;
+                    let (payload, properties) = parse_properties(rest, version)?;
+                    Packet::Auth {
+                        reason_code,
+                        properties,
+                        payload,
+                    }
+                }
+            }
+        };
+        Ok((input, packet))
+    }
+
+    /// Encode the packet back to its wire representation: the fixed header
+    /// followed by the variable header/payload bytes. Properties are only
+    /// written back for packets that parsed a non-empty property block, so a
+    /// v3.1.1 packet round-trips to the exact bytes it was parsed from.
+    /// DISCONNECT is the exception: with a non-zero reason code its property
+    /// block is written even when empty.
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (packet_type, packet_flags, body): (PacketType, u8, Cow<[u8]>) = match self {
+            Packet::Connect {
+                protocol_name,
+                protocol_level,
+                connect_flags,
+                keep_alive,
+                properties,
+                payload,
+            } => {
+                let mut body = Vec::new();
+                body.extend_from_slice(&(protocol_name.len() as u16).to_be_bytes());
+                body.extend_from_slice(protocol_name.as_bytes());
+                body.push(*protocol_level);
+                body.push(*connect_flags);
+                body.extend_from_slice(&keep_alive.to_be_bytes());
+                body.extend_from_slice(&encode_body(properties, payload));
+                (PacketType::Connect, 0, Cow::Owned(body))
+            }
+            Packet::Connack {
+                session_present,
+                reason_code,
+                properties,
+                payload,
+            } => {
+                let mut body = Vec::new();
+                body.push(if *session_present { 0b1 } else { 0 });
+                body.push(*reason_code);
+                body.extend_from_slice(&encode_body(properties, payload));
+                (PacketType::Connack, 0, Cow::Owned(body))
+            }
+            Packet::Publish {
+                dup,
+                qos,
+                retain,
+                topic_name,
+                properties,
+                payload,
+            } => {
+                let mut packet_flags = (*qos as u8) << 1;
+                if *dup {
+                    packet_flags |= 0b1000;
+                }
+                if *retain {
+                    packet_flags |= 0b0001;
+                }
+                let mut body = Vec::new();
+                topic::encode(topic_name, &mut body);
+                if !properties.is_empty() {
+                    properties.encode(&mut body);
+                }
+                body.extend_from_slice(payload);
+                (PacketType::Publish, packet_flags, Cow::Owned(body))
+            }
+            Packet::Puback(payload) => (PacketType::Puback, 0, Cow::Borrowed(*payload)),
+            Packet::PubRec(payload) => (PacketType::PubRec, 0, Cow::Borrowed(*payload)),
+            Packet::PubRel(payload) => (PacketType::PubRel, 0b0010, Cow::Borrowed(*payload)),
+            Packet::PubComp(payload) => (PacketType::PubComp, 0, Cow::Borrowed(*payload)),
+            Packet::Subscribe {
+                packet_id,
+                properties,
+                topics,
+            } => {
+                let mut body = Vec::new();
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                if !properties.is_empty() {
+                    properties.encode(&mut body);
+                }
+                for (filter, options) in topics {
+                    topic::encode(filter, &mut body);
+                    body.push(*options);
+                }
+                (PacketType::Subscribe, 0b0010, Cow::Owned(body))
+            }
+            Packet::Suback(payload) => (PacketType::Suback, 0, Cow::Borrowed(*payload)),
+            Packet::Unsubscribe {
+                packet_id,
+                properties,
+                topics,
+            } => {
+                let mut body = Vec::new();
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                if !properties.is_empty() {
+                    properties.encode(&mut body);
+                }
+                for filter in topics {
+                    topic::encode(filter, &mut body);
+                }
+                (PacketType::Unsubscribe, 0b0010, Cow::Owned(body))
+            }
+            Packet::Unsuback(payload) => (PacketType::Unsuback, 0, Cow::Borrowed(*payload)),
+            Packet::PingReq => (PacketType::PingReq, 0, Cow::Borrowed(&[][..])),
+            Packet::PingResp => (PacketType::PingResp, 0, Cow::Borrowed(&[][..])),
+            Packet::Disconnect {
+                reason_code,
+                properties,
+            } => {
+                let body = if *reason_code == 0 && properties.is_empty() {
+                    Cow::Borrowed(&[][..])
+                } else {
+                    let mut body = Vec::new();
+                    body.push(*reason_code);
+                    properties.encode(&mut body);
+                    Cow::Owned(body)
+                };
+                (PacketType::Disconnect, 0, body)
+            }
+            Packet::Auth {
+                reason_code,
+                properties,
+                payload,
+            } => {
+                let body = if *reason_code == 0 && properties.is_empty() && payload.is_empty() {
+                    Cow::Borrowed(&[][..])
+                } else {
+                    let mut body = Vec::new();
+                    body.push(*reason_code);
+                    body.extend_from_slice(&encode_body(properties, payload));
+                    Cow::Owned(body)
+                };
+                (PacketType::Auth, 0, body)
+            }
+        };
+
+        let header = FixedHeader {
+            packet_type,
+            packet_flags,
+            remaining_length: body.len() as u32,
+        };
+        header.encode(buf);
+        buf.extend_from_slice(&body);
+    }
+}
+
+/// Prefix `payload` with its encoded property block, unless `properties` is
+/// empty (the v3.1.1 case), in which case the payload is returned unchanged.
+fn encode_body<'a>(properties: &Properties<'a>, payload: &'a [u8]) -> Cow<'a, [u8]> {
+    if properties.is_empty() {
+        Cow::Borrowed(payload)
+    } else {
+        let mut body = Vec::new();
+        properties.encode(&mut body);
+        body.extend_from_slice(payload);
+        Cow::Owned(body)
+    }
 }
 
 /// Parse first byte of MQTT Fixed header returning the packet type and flags specific to each MQTT Control Packet Type
-fn packet_byte(input: &[u8]) -> IResult<&[u8], (PacketType, u8)> {
+fn packet_byte(input: &[u8], version: ProtocolVersion) -> IResult<&[u8], (PacketType, u8), Error> {
     let packet_parser = bits::<_, _, nom::error::Error<(&[u8], usize)>, _, _>(tuple((
         take(4_usize),
         take(4_usize),
     )));
-    map_res::<_, _, _, _, InvalidPacketTypeError, _, _>(
-        packet_parser,
-        |(packet_type, packet_flags): (u8, u8)| Ok((packet_type.try_into()?, packet_flags)),
-    )(input)
+    map_res::<_, _, _, _, Error, _, _>(packet_parser, move |(packet_type, packet_flags): (u8, u8)| {
+        let packet_type = PacketType::decode(packet_type, version)?;
+        validate_fixed_flags(&packet_type, packet_flags)?;
+        Ok((packet_type, packet_flags))
+    })(input)
 }
 
-/// Remaining length can be up to four bytes depending on most significant bit being 1
-fn remaining_length(input: &[u8]) -> IResult<&[u8], i32> {
-    let mut accumulator = 0_i32;
-    let mut index = 0_u32;
+/// Remaining length is a variable byte integer of at most four bytes (max value
+/// 268,435,455): each byte contributes its low 7 bits, with the high bit
+/// signalling that another byte follows. A continuation bit still set after
+/// four bytes is a malformed encoding.
+pub(crate) fn remaining_length(input: &[u8]) -> IResult<&[u8], u32, Error> {
+    let mut accumulator: u32 = 0;
+    let mut multiplier: u32 = 1;
     let mut input = input;
-    loop {
-        let _input = input.clone();
+
+    for _ in 0..4 {
         // Read off the most significant bit as continuation indicator and remaining 7 bits as the value
         let (i, (continuation, length_value)): (_, (u8, u8)) =
             bits::<_, _, nom::error::Error<(&[u8], usize)>, _, _>(tuple((
                 take(1_usize),
                 take(7_usize),
-            )))(_input)?;
+            )))(input)?;
 
         input = i;
+        accumulator += (length_value as u32) * multiplier;
 
-        // Add as if sequential bytes
-        accumulator = accumulator + ((length_value as i32) << index);
-
-        // Break if most significant bit isn't 1 and hence all remaining bits being 1 i.e. 0xFF
-        // indicating there's more bytes in the remaining length value
         if continuation != 1 {
-            break;
+            return Ok((input, accumulator));
         }
 
-        index += 7;
+        multiplier *= 128;
     }
 
-    Ok((input, accumulator))
+    Err(nom::Err::Failure(Error::MalformedRemainingLength))
+}
+
+/// A 2-byte big-endian length-prefixed UTF-8 string, as used throughout MQTT
+/// (topic names/filters, client ids, user properties, ...). `on_invalid_utf8`
+/// builds the context-specific error to return if the bytes aren't valid UTF-8.
+pub(crate) fn utf8_string<'a>(
+    input: &'a [u8],
+    on_invalid_utf8: impl FnOnce() -> Error,
+) -> IResult<&'a [u8], &'a str, Error> {
+    let (input, length) = be_u16(input)?;
+    let (input, bytes) = take_bytes(length as usize)(input)?;
+    let value = std::str::from_utf8(bytes).map_err(|_| nom::Err::Failure(on_invalid_utf8()))?;
+    Ok((input, value))
 }
 
 #[cfg(test)]
@@ -156,6 +805,565 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_decode_packet_pingreq() {
+        assert_eq!(
+            Packet::parse(b"\xc0\x00"),
+            nom::IResult::Ok((&b""[..], Packet::PingReq))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_pingreq_rejects_nonempty_body() {
+        assert_eq!(
+            Packet::parse(b"\xc0\x02\xaa\xbb"),
+            Err(nom::Err::Failure(Error::PayloadNotEmpty(
+                PacketType::PingReq
+            )))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_pingresp_rejects_nonempty_body() {
+        assert_eq!(
+            Packet::parse(b"\xd0\x01\xaa"),
+            Err(nom::Err::Failure(Error::PayloadNotEmpty(
+                PacketType::PingResp
+            )))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_disconnect() {
+        assert_eq!(
+            Packet::parse(b"\xe0\x00"),
+            nom::IResult::Ok((
+                &b""[..],
+                Packet::Disconnect {
+                    reason_code: 0,
+                    properties: Properties::default(),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_disconnect_rejects_nonempty_body_for_v311() {
+        assert_eq!(
+            Packet::parse(b"\xe0\x02\x00\x00"),
+            Err(nom::Err::Failure(Error::PayloadNotEmpty(
+                PacketType::Disconnect
+            )))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_disconnect_v5_parses_reason_code_and_properties() {
+        use properties::Property;
+
+        assert_eq!(
+            Packet::parse_with_version(b"\xe0\x04\x81\x02\x01\x01", ProtocolVersion::V5),
+            Ok((
+                &b""[..],
+                Packet::Disconnect {
+                    reason_code: 0x81,
+                    properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_disconnect_v5_rejects_trailing_bytes() {
+        // reason code + properties leave a trailing byte DISCONNECT has no field for
+        assert_eq!(
+            Packet::parse_with_version(b"\xe0\x05\x81\x02\x01\x01\xff", ProtocolVersion::V5),
+            Err(nom::Err::Failure(Error::PayloadNotEmpty(
+                PacketType::Disconnect
+            )))
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_disconnect_v5_round_trips() {
+        use properties::Property;
+
+        let packet = Packet::Disconnect {
+            reason_code: 0x81,
+            properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\xe0\x04\x81\x02\x01\x01");
+        assert_eq!(
+            Packet::parse_with_version(&buf, ProtocolVersion::V5),
+            Ok((&b""[..], packet))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_leaves_trailing_bytes_for_next_packet() {
+        // A Connack packet followed by the start of another packet in the buffer
+        assert_eq!(
+            Packet::parse(b"\x20\x02\x00\x00\xc0\x00"),
+            nom::IResult::Ok((
+                &b"\xc0\x00"[..],
+                Packet::Connack {
+                    session_present: false,
+                    reason_code: 0,
+                    properties: Properties::default(),
+                    payload: &b""[..],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_connack_v5_consumes_ack_flags_and_reason_code_before_properties() {
+        use properties::Property;
+
+        // ack_flags=0x01 (session present), reason_code=0x00 (success), then a
+        // 2-byte property block (PayloadFormatIndicator(1))
+        assert_eq!(
+            Packet::parse_with_version(b"\x20\x05\x01\x00\x02\x01\x01", ProtocolVersion::V5),
+            Ok((
+                &b""[..],
+                Packet::Connack {
+                    session_present: true,
+                    reason_code: 0,
+                    properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+                    payload: &b""[..],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_connack_v5_round_trips() {
+        use properties::Property;
+
+        let packet = Packet::Connack {
+            session_present: true,
+            reason_code: 0,
+            properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+            payload: &b""[..],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\x20\x05\x01\x00\x02\x01\x01");
+        assert_eq!(
+            Packet::parse_with_version(&buf, ProtocolVersion::V5),
+            Ok((&b""[..], packet))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_connect_v5_consumes_variable_header_before_properties() {
+        use properties::Property;
+
+        // protocol name "MQTT", level 5, connect_flags 0x02, keep_alive 60,
+        // then a 2-byte property block (PayloadFormatIndicator(1)) and a
+        // single payload byte
+        assert_eq!(
+            Packet::parse_with_version(
+                b"\x10\x0e\x00\x04MQTT\x05\x02\x00\x3c\x02\x01\x01\xab",
+                ProtocolVersion::V5,
+            ),
+            Ok((
+                &b""[..],
+                Packet::Connect {
+                    protocol_name: "MQTT",
+                    protocol_level: 5,
+                    connect_flags: 0x02,
+                    keep_alive: 60,
+                    properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+                    payload: &b"\xab"[..],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_connect_v5_round_trips() {
+        use properties::Property;
+
+        let packet = Packet::Connect {
+            protocol_name: "MQTT",
+            protocol_level: 5,
+            connect_flags: 0x02,
+            keep_alive: 60,
+            properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+            payload: &b"\xab"[..],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\x10\x0e\x00\x04MQTT\x05\x02\x00\x3c\x02\x01\x01\xab");
+        assert_eq!(
+            Packet::parse_with_version(&buf, ProtocolVersion::V5),
+            Ok((&b""[..], packet))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_publish_flags() {
+        // Publish with dup=1, qos=ExactlyOnce, retain=0, topic "t" and a single payload byte
+        assert_eq!(
+            Packet::parse(b"\x3C\x04\x00\x01t\xff"),
+            nom::IResult::Ok((
+                &b""[..],
+                Packet::Publish {
+                    dup: true,
+                    qos: QoS::ExactlyOnce,
+                    retain: false,
+                    topic_name: "t",
+                    properties: Properties::default(),
+                    payload: &b"\xff"[..],
+                },
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_publish_reserved_qos_is_rejected() {
+        // Flag nibble 0b0110 sets both QoS bits, the reserved value 3
+        assert!(Packet::parse(b"\x36\x00").is_err());
+    }
+
+    #[test]
+    fn test_decode_fixed_header_pubrel_requires_reserved_flags() {
+        assert!(FixedHeader::parse(b"\x60\x00").is_err());
+        assert_eq!(
+            FixedHeader::parse(b"\x62\x00"),
+            nom::IResult::Ok((
+                &b""[..],
+                FixedHeader {
+                    packet_type: PacketType::PubRel,
+                    packet_flags: 0b0010,
+                    remaining_length: 0,
+                },
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_fixed_header_incomplete_reports_bytes_needed() {
+        // Only the packet type/flags byte is buffered, the remaining length byte hasn't arrived yet
+        assert!(matches!(
+            FixedHeader::parse(b"\x20"),
+            Err(nom::Err::Incomplete(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_packet_incomplete_reports_bytes_needed() {
+        // Connack header declares remaining_length 2, but only one body byte is buffered
+        assert_eq!(
+            Packet::parse(b"\x20\x02\x00"),
+            Err(nom::Err::Incomplete(nom::Needed::new(1)))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_completes_once_buffer_fills() {
+        let mut buf = b"\x20\x02\x00".to_vec();
+        assert!(Packet::parse(&buf).is_err());
+
+        buf.push(0x00);
+        assert_eq!(
+            Packet::parse(&buf),
+            Ok((
+                &b""[..],
+                Packet::Connack {
+                    session_present: false,
+                    reason_code: 0,
+                    properties: Properties::default(),
+                    payload: &b""[..],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_encode_fixed_header_round_trips() {
+        let header = FixedHeader {
+            packet_type: PacketType::Connack,
+            packet_flags: 0,
+            remaining_length: 127,
+        };
+        let mut buf = Vec::new();
+        header.encode(&mut buf);
+        assert_eq!(buf, b"\x20\x7f");
+        assert_eq!(FixedHeader::parse(&buf), Ok((&b""[..], header)));
+    }
+
+    #[test]
+    fn test_encode_fixed_header_multi_byte_remaining_length_round_trips() {
+        let header = FixedHeader {
+            packet_type: PacketType::Connect,
+            packet_flags: 0,
+            remaining_length: 321,
+        };
+        let mut buf = Vec::new();
+        header.encode(&mut buf);
+        assert_eq!(buf, b"\x10\xc1\x02");
+        assert_eq!(FixedHeader::parse(&buf), Ok((&b""[..], header)));
+    }
+
+    #[test]
+    fn test_encode_packet_publish_round_trips() {
+        let packet = Packet::Publish {
+            dup: true,
+            qos: QoS::ExactlyOnce,
+            retain: false,
+            topic_name: "t",
+            properties: Properties::default(),
+            payload: &b"\xff"[..],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\x3C\x04\x00\x01t\xff");
+        assert_eq!(Packet::parse(&buf), Ok((&b""[..], packet)));
+    }
+
+    #[test]
+    fn test_encode_packet_pingreq_round_trips() {
+        let packet = Packet::PingReq;
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\xc0\x00");
+        assert_eq!(Packet::parse(&buf), Ok((&b""[..], packet)));
+    }
+
+    #[test]
+    fn test_encode_packet_pubrel_sets_reserved_flags() {
+        let packet = Packet::PubRel(&b""[..]);
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\x62\x00");
+        assert_eq!(Packet::parse(&buf), Ok((&b""[..], packet)));
+    }
+
+    #[test]
+    fn test_decode_fixed_header_auth_rejected_for_v311() {
+        assert!(FixedHeader::parse(b"\xf0\x00").is_err());
+    }
+
+    #[test]
+    fn test_decode_fixed_header_auth_accepted_for_v5() {
+        assert_eq!(
+            FixedHeader::parse_with_version(b"\xf0\x00", ProtocolVersion::V5),
+            Ok((
+                &b""[..],
+                FixedHeader {
+                    packet_type: PacketType::Auth,
+                    packet_flags: 0,
+                    remaining_length: 0,
+                },
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_auth_v5() {
+        assert_eq!(
+            Packet::parse_with_version(b"\xf0\x00", ProtocolVersion::V5),
+            Ok((
+                &b""[..],
+                Packet::Auth {
+                    reason_code: 0,
+                    payload: &b""[..],
+                    properties: Properties::default(),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_auth_v5_parses_properties() {
+        use properties::Property;
+
+        // remaining_length 4: reason code, then a 2-byte property block
+        // (PayloadFormatIndicator(1)), followed by no further payload
+        assert_eq!(
+            Packet::parse_with_version(b"\xf0\x04\x00\x02\x01\x01", ProtocolVersion::V5),
+            Ok((
+                &b""[..],
+                Packet::Auth {
+                    reason_code: 0,
+                    payload: &b""[..],
+                    properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_publish_v5_parses_properties() {
+        use properties::Property;
+
+        // topic "t", a 2-byte property block (PayloadFormatIndicator(1)), then a single payload byte
+        assert_eq!(
+            Packet::parse_with_version(b"\x30\x07\x00\x01t\x02\x01\x01\xff", ProtocolVersion::V5),
+            Ok((
+                &b""[..],
+                Packet::Publish {
+                    dup: false,
+                    qos: QoS::AtMostOnce,
+                    retain: false,
+                    topic_name: "t",
+                    properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+                    payload: &b"\xff"[..],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_publish_rejects_wildcard_topic_name() {
+        // topic "a/+" is not a valid topic *name* (wildcards are filter-only)
+        assert_eq!(
+            Packet::parse(b"\x30\x05\x00\x03a/+"),
+            Err(nom::Err::Failure(Error::TopicMalformed))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_subscribe_parses_topics() {
+        assert_eq!(
+            Packet::parse(b"\x82\x06\x00\x01\x00\x01a\x01"),
+            Ok((
+                &b""[..],
+                Packet::Subscribe {
+                    packet_id: 1,
+                    properties: Properties::default(),
+                    topics: vec![("a", 1)],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_subscribe_rejects_malformed_topic_filter() {
+        // "a#" is not a valid topic filter: '#' must be a whole, final level
+        assert_eq!(
+            Packet::parse(b"\x82\x07\x00\x01\x00\x02a#\x01"),
+            Err(nom::Err::Failure(Error::TopicMalformed))
+        );
+    }
+
+    #[test]
+    fn test_decode_packet_subscribe_packet_id_zero_is_rejected() {
+        assert_eq!(
+            Packet::parse(b"\x82\x02\x00\x00"),
+            Err(nom::Err::Failure(Error::PacketIdZero))
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_subscribe_round_trips() {
+        let packet = Packet::Subscribe {
+            packet_id: 1,
+            properties: Properties::default(),
+            topics: vec![("a", 1)],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\x82\x06\x00\x01\x00\x01a\x01");
+        assert_eq!(Packet::parse(&buf), Ok((&b""[..], packet)));
+    }
+
+    #[test]
+    fn test_decode_packet_unsubscribe_parses_topics() {
+        assert_eq!(
+            Packet::parse(b"\xA2\x05\x00\x01\x00\x01a"),
+            Ok((
+                &b""[..],
+                Packet::Unsubscribe {
+                    packet_id: 1,
+                    properties: Properties::default(),
+                    topics: vec!["a"],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_unsubscribe_round_trips() {
+        let packet = Packet::Unsubscribe {
+            packet_id: 1,
+            properties: Properties::default(),
+            topics: vec!["a"],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\xA2\x05\x00\x01\x00\x01a");
+        assert_eq!(Packet::parse(&buf), Ok((&b""[..], packet)));
+    }
+
+    #[test]
+    fn test_decode_packet_unsubscribe_v5_parses_properties() {
+        use properties::Property;
+
+        // packet id, a 2-byte property block (PayloadFormatIndicator(1)), then one topic filter
+        assert_eq!(
+            Packet::parse_with_version(
+                b"\xA2\x08\x00\x01\x02\x01\x01\x00\x01a",
+                ProtocolVersion::V5,
+            ),
+            Ok((
+                &b""[..],
+                Packet::Unsubscribe {
+                    packet_id: 1,
+                    properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+                    topics: vec!["a"],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_unsubscribe_v5_with_properties_round_trips() {
+        use properties::Property;
+
+        let packet = Packet::Unsubscribe {
+            packet_id: 1,
+            properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+            topics: vec!["a"],
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf, b"\xA2\x08\x00\x01\x02\x01\x01\x00\x01a");
+        assert_eq!(
+            Packet::parse_with_version(&buf, ProtocolVersion::V5),
+            Ok((&b""[..], packet))
+        );
+    }
+
+    #[test]
+    fn test_encode_packet_auth_v5_with_properties_round_trips() {
+        use properties::Property;
+
+        let packet = Packet::Auth {
+            reason_code: 0,
+            payload: &b""[..],
+            properties: Properties(vec![Property::PayloadFormatIndicator(1)]),
+        };
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        assert_eq!(
+            Packet::parse_with_version(&buf, ProtocolVersion::V5),
+            Ok((&b""[..], packet))
+        );
+    }
+
+    #[test]
+    fn test_decode_fixed_header_remaining_length_more_than_four_bytes_is_malformed() {
+        assert_eq!(
+            FixedHeader::parse(b"\x20\xff\xff\xff\xff\x01"),
+            Err(nom::Err::Failure(Error::MalformedRemainingLength))
+        );
+    }
+
     #[test]
     fn test_decode_fixed_header_multiple_remaining_length() {
         assert_eq!(
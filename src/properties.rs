@@ -0,0 +1,239 @@
+//! MQTT v5 properties: the `(identifier, value)` pairs that follow the
+//! variable header of CONNECT, CONNACK, PUBLISH, SUBSCRIBE, DISCONNECT, AUTH
+//! and other v5 packets, introduced by a variable-byte-integer byte length.
+
+use nom::{
+    bytes::streaming::take,
+    number::streaming::{be_u32, be_u8},
+    IResult,
+};
+
+use crate::{encode_remaining_length, remaining_length, utf8_string, Error};
+
+/// A single decoded MQTT v5 property. The value type is fixed per identifier,
+/// e.g. Payload Format Indicator (0x01) is always a `u8`, Content Type (0x03)
+/// is always a UTF-8 string.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Property<'a> {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(&'a str),
+    SubscriptionIdentifier(u32),
+    UserProperty(&'a str, &'a str),
+}
+
+/// The properties attached to a packet's variable header.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct Properties<'a>(pub(crate) Vec<Property<'a>>);
+
+impl<'a> Properties<'a> {
+    /// Parse a property block: a variable-byte-integer byte-length followed
+    /// by that many bytes of `(identifier, value)` pairs.
+    pub(crate) fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self, Error> {
+        let (input, length) = remaining_length(input)?;
+        let (input, mut property_bytes) = take(length as usize)(input)?;
+
+        let mut properties = Vec::new();
+        while !property_bytes.is_empty() {
+            let (rest, property) = parse_property(property_bytes)?;
+            property_bytes = rest;
+            properties.push(property);
+        }
+
+        Ok((input, Properties(properties)))
+    }
+
+    /// Encode the property block back to its wire representation: the
+    /// variable-byte-integer byte-length of the encoded properties followed
+    /// by the `(identifier, value)` pairs themselves.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let mut body = Vec::new();
+        for property in &self.0 {
+            property.encode(&mut body);
+        }
+        encode_remaining_length(body.len() as u32, buf);
+        buf.extend_from_slice(&body);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<'a> Property<'a> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Property::PayloadFormatIndicator(value) => {
+                buf.push(0x01);
+                buf.push(*value);
+            }
+            Property::MessageExpiryInterval(value) => {
+                buf.push(0x02);
+                buf.extend_from_slice(&value.to_be_bytes());
+            }
+            Property::ContentType(value) => {
+                buf.push(0x03);
+                encode_utf8_string(value, buf);
+            }
+            Property::SubscriptionIdentifier(value) => {
+                buf.push(0x0B);
+                encode_remaining_length(*value, buf);
+            }
+            Property::UserProperty(key, value) => {
+                buf.push(0x26);
+                encode_utf8_string(key, buf);
+                encode_utf8_string(value, buf);
+            }
+        }
+    }
+}
+
+fn encode_utf8_string(value: &str, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn parse_property(input: &[u8]) -> IResult<&[u8], Property, Error> {
+    let (input, identifier) = be_u8(input)?;
+    match identifier {
+        0x01 => {
+            let (input, value) = be_u8(input)?;
+            Ok((input, Property::PayloadFormatIndicator(value)))
+        }
+        0x02 => {
+            let (input, value) = be_u32(input)?;
+            Ok((input, Property::MessageExpiryInterval(value)))
+        }
+        0x03 => {
+            let (input, value) = utf8_string(input, || Error::MalformedUtf8String)?;
+            Ok((input, Property::ContentType(value)))
+        }
+        0x0B => {
+            let (input, value) = remaining_length(input)?;
+            Ok((input, Property::SubscriptionIdentifier(value)))
+        }
+        0x26 => {
+            let (input, key) = utf8_string(input, || Error::MalformedUtf8String)?;
+            let (input, value) = utf8_string(input, || Error::MalformedUtf8String)?;
+            Ok((input, Property::UserProperty(key, value)))
+        }
+        _ => Err(nom::Err::Failure(Error::InvalidPropertyIdentifier(
+            identifier,
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty_properties() {
+        assert_eq!(
+            Properties::parse(b"\x00"),
+            Ok((&b""[..], Properties(vec![])))
+        );
+    }
+
+    #[test]
+    fn test_parse_payload_format_indicator() {
+        assert_eq!(
+            Properties::parse(b"\x02\x01\x01"),
+            Ok((
+                &b""[..],
+                Properties(vec![Property::PayloadFormatIndicator(1)])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_message_expiry_interval() {
+        assert_eq!(
+            Properties::parse(b"\x05\x02\x00\x00\x00\x0a"),
+            Ok((
+                &b""[..],
+                Properties(vec![Property::MessageExpiryInterval(10)])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_content_type() {
+        assert_eq!(
+            Properties::parse(b"\x07\x03\x00\x04json"),
+            Ok((&b""[..], Properties(vec![Property::ContentType("json")])))
+        );
+    }
+
+    #[test]
+    fn test_parse_subscription_identifier() {
+        assert_eq!(
+            Properties::parse(b"\x02\x0b\x7f"),
+            Ok((
+                &b""[..],
+                Properties(vec![Property::SubscriptionIdentifier(127)])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_user_property() {
+        assert_eq!(
+            Properties::parse(b"\x0b\x26\x00\x03key\x00\x03val"),
+            Ok((
+                &b""[..],
+                Properties(vec![Property::UserProperty("key", "val")])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_multiple_properties() {
+        assert_eq!(
+            Properties::parse(b"\x07\x01\x01\x02\x00\x00\x00\x0a"),
+            Ok((
+                &b""[..],
+                Properties(vec![
+                    Property::PayloadFormatIndicator(1),
+                    Property::MessageExpiryInterval(10),
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_unknown_identifier_is_rejected() {
+        assert_eq!(
+            Properties::parse(b"\x01\xff"),
+            Err(nom::Err::Failure(Error::InvalidPropertyIdentifier(0xff)))
+        );
+    }
+
+    #[test]
+    fn test_parse_content_type_invalid_utf8_is_rejected() {
+        assert_eq!(
+            Properties::parse(b"\x05\x03\x00\x02\xff\xfe"),
+            Err(nom::Err::Failure(Error::MalformedUtf8String))
+        );
+    }
+
+    #[test]
+    fn test_encode_empty_properties_round_trips() {
+        let properties = Properties::default();
+        let mut buf = Vec::new();
+        properties.encode(&mut buf);
+        assert_eq!(buf, b"\x00");
+        assert_eq!(Properties::parse(&buf), Ok((&b""[..], properties)));
+    }
+
+    #[test]
+    fn test_encode_multiple_properties_round_trips() {
+        let properties = Properties(vec![
+            Property::PayloadFormatIndicator(1),
+            Property::UserProperty("key", "val"),
+        ]);
+        let mut buf = Vec::new();
+        properties.encode(&mut buf);
+        assert_eq!(Properties::parse(&buf), Ok((&b""[..], properties)));
+    }
+}
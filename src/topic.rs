@@ -0,0 +1,129 @@
+//! Topic name and topic filter parsing, used by PUBLISH, SUBSCRIBE and
+//! UNSUBSCRIBE: a 2-byte length-prefixed UTF-8 string plus the `+`/`#`
+//! wildcard rules.
+//! ref: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718107
+
+use nom::IResult;
+
+use crate::{utf8_string, Error};
+
+/// Parse a 2-byte length-prefixed UTF-8 topic string. Wildcard validity
+/// depends on whether it's used as a published name or a subscription
+/// filter, so callers should follow up with `validate_topic_name` or
+/// `validate_topic_filter`.
+pub(crate) fn topic(input: &[u8]) -> IResult<&[u8], &str, Error> {
+    utf8_string(input, || Error::TopicNotUtf8)
+}
+
+/// Encode a topic string back to its 2-byte length-prefixed wire representation.
+pub(crate) fn encode(value: &str, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// A topic name, as published to, must not contain the `+`/`#` wildcard
+/// characters or a NUL byte.
+pub(crate) fn validate_topic_name(name: &str) -> Result<(), Error> {
+    if name.contains('\u{0}') || name.contains('+') || name.contains('#') {
+        return Err(Error::TopicMalformed);
+    }
+    Ok(())
+}
+
+/// A topic filter, as subscribed to, may use `+` only as a whole level and
+/// `#` only as the final level, and must not contain a NUL byte.
+pub(crate) fn validate_topic_filter(filter: &str) -> Result<(), Error> {
+    if filter.contains('\u{0}') {
+        return Err(Error::TopicMalformed);
+    }
+
+    let levels: Vec<&str> = filter.split('/').collect();
+    let last_index = levels.len() - 1;
+    for (index, level) in levels.iter().enumerate() {
+        if level.contains('#') && (*level != "#" || index != last_index) {
+            return Err(Error::TopicMalformed);
+        }
+        if level.contains('+') && *level != "+" {
+            return Err(Error::TopicMalformed);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_topic() {
+        assert_eq!(
+            topic(b"\x00\x04test"),
+            Ok((&b""[..], "test"))
+        );
+    }
+
+    #[test]
+    fn test_encode_topic_round_trips() {
+        let mut buf = Vec::new();
+        encode("test", &mut buf);
+        assert_eq!(buf, b"\x00\x04test");
+        assert_eq!(topic(&buf), Ok((&b""[..], "test")));
+    }
+
+    #[test]
+    fn test_parse_topic_invalid_utf8() {
+        assert_eq!(
+            topic(b"\x00\x02\xff\xfe"),
+            Err(nom::Err::Failure(Error::TopicNotUtf8))
+        );
+    }
+
+    #[test]
+    fn test_validate_topic_name_rejects_plus_wildcard() {
+        assert_eq!(validate_topic_name("a/+/c"), Err(Error::TopicMalformed));
+    }
+
+    #[test]
+    fn test_validate_topic_name_rejects_hash_wildcard() {
+        assert_eq!(validate_topic_name("a/#"), Err(Error::TopicMalformed));
+    }
+
+    #[test]
+    fn test_validate_topic_name_accepts_plain_topic() {
+        assert_eq!(validate_topic_name("a/b/c"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_topic_filter_accepts_single_level_plus() {
+        assert_eq!(validate_topic_filter("a/+/c"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_topic_filter_rejects_plus_within_level() {
+        assert_eq!(validate_topic_filter("a/b+/c"), Err(Error::TopicMalformed));
+    }
+
+    #[test]
+    fn test_validate_topic_filter_accepts_trailing_hash() {
+        assert_eq!(validate_topic_filter("a/b/#"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_topic_filter_rejects_hash_not_in_final_level() {
+        assert_eq!(validate_topic_filter("a/#/c"), Err(Error::TopicMalformed));
+    }
+
+    #[test]
+    fn test_validate_topic_filter_rejects_hash_within_level() {
+        assert_eq!(validate_topic_filter("a/b#/c"), Err(Error::TopicMalformed));
+    }
+
+    #[test]
+    fn test_validate_topic_filter_rejects_nul_byte() {
+        assert_eq!(
+            validate_topic_filter("a/\u{0}/c"),
+            Err(Error::TopicMalformed)
+        );
+    }
+}